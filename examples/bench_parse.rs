@@ -1,16 +1,22 @@
 extern crate bitcoin;
 extern crate electrs;
+extern crate hex;
 
 #[macro_use]
 extern crate log;
 #[macro_use]
 extern crate error_chain;
 
+use std::fs::File;
+use std::io::{self, Write};
+
 use electrs::{bulk::Parser,
-              config::Config,
+              config::{Action, Config},
               daemon::Daemon,
               errors::*,
+              fd_limit::raise_fd_limit,
               metrics::Metrics,
+              new_index::db::inspect_version,
               signal::Waiter,
               store::{DBStore, StoreOptions, WriteStore}};
 
@@ -18,14 +24,45 @@ use error_chain::ChainedError;
 
 fn run(config: Config) -> Result<()> {
     let signal = Waiter::new();
+    raise_fd_limit(config.max_open_files);
     let metrics = Metrics::new(config.monitoring_addr);
     metrics.start();
 
     let daemon = Daemon::new(config.network_type, &metrics)?;
-    let store = DBStore::open("./test-db", StoreOptions { bulk_import: true });
 
-    let chan = Parser::new(&daemon, &store, &metrics)?.start();
-    store.load(chan, &signal)
+    match config.action {
+        Action::Import => {
+            let store = DBStore::open("./test-db", StoreOptions { bulk_import: true });
+            let chan = Parser::new(&daemon, &store, &metrics)?.start();
+            store.load(chan, &signal)
+        }
+        Action::Compact => {
+            let store = DBStore::open("./test-db", StoreOptions { bulk_import: false });
+            store.full_compaction();
+            Ok(())
+        }
+        Action::Version => {
+            match inspect_version(&config.db_path, &config)? {
+                Some(version) => println!("{}", version),
+                None => println!("(no DB found at {:?})", config.db_path),
+            }
+            Ok(())
+        }
+        Action::Export { prefix, output } => {
+            let store = DBStore::open("./test-db", StoreOptions { bulk_import: false });
+            let mut writer: Box<Write> = match output {
+                Some(path) => Box::new(
+                    File::create(&path).chain_err(|| format!("failed to create {:?}", path))?,
+                ),
+                None => Box::new(io::stdout()),
+            };
+            for row in store.iter_scan(&prefix) {
+                writeln!(writer, "{} {}", hex::encode(&row.key), hex::encode(&row.value))
+                    .chain_err(|| "failed to write export row")?;
+            }
+            Ok(())
+        }
+    }
 }
 
 fn main() {
@@ -1,14 +1,54 @@
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, SubCommand};
+use hex;
+use serde::Deserialize;
 use std::env::home_dir;
 use std::fs;
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use stderrlog;
+use toml;
 
 use daemon::Network;
+use new_index::db::DbCompactionProfile;
 
 use errors::*;
 
+// the maintenance or data-extraction operation to run, selected via a clap subcommand
+#[derive(Debug)]
+pub enum Action {
+    Import,
+    Compact,
+    // each matching row is written as a hex-encoded "key value" line
+    Export {
+        prefix: Vec<u8>,
+        output: Option<PathBuf>,
+    },
+    Version,
+}
+
+fn parse_hex_prefix(s: &str) -> Vec<u8> {
+    hex::decode(s).expect("invalid hex prefix")
+}
+
+// precedence: explicit CLI flag > config file value > built-in default
+fn resolve(cli: Option<&str>, file: Option<String>, default: &str) -> String {
+    cli.map(|s| s.to_owned())
+        .or(file)
+        .unwrap_or_else(|| default.to_owned())
+}
+
+// rejects values that don't fit the i32 RocksDB's set_max_open_files expects
+fn validate_max_open_files(parsed: u64) -> u64 {
+    if parsed > i32::max_value() as u64 {
+        panic!(
+            "--max-open-files {} exceeds the maximum RocksDB max_open_files value of {}",
+            parsed,
+            i32::max_value()
+        );
+    }
+    parsed
+}
+
 fn read_cookie(daemon_dir: &Path) -> Result<String> {
     let mut path = daemon_dir.to_path_buf();
     path.push(".cookie");
@@ -18,6 +58,46 @@ fn read_cookie(daemon_dir: &Path) -> Result<String> {
     Ok(contents.trim().to_owned())
 }
 
+// mirrors the CLI-settable fields of Config; all optional so a file only needs
+// to specify the values it wants to override
+#[derive(Debug, Default, Deserialize)]
+struct ConfigFile {
+    network: Option<String>,
+    db_dir: Option<String>,
+    daemon_dir: Option<String>,
+    cookie: Option<String>,
+    daemon_rpc_addr: Option<String>,
+    electrum_rpc_addr: Option<String>,
+    monitoring_addr: Option<String>,
+    db_compaction_profile: Option<String>,
+    max_open_files: Option<u64>,
+}
+
+impl ConfigFile {
+    // falls back to an implicit electrs.toml inside daemon_dir if no path is given
+    fn load(path: Option<&str>, daemon_dir: &str) -> ConfigFile {
+        let path = match path {
+            Some(path) => Some(PathBuf::from(path)),
+            None => {
+                let implicit = Path::new(daemon_dir).join("electrs.toml");
+                if implicit.exists() {
+                    Some(implicit)
+                } else {
+                    None
+                }
+            }
+        };
+        let path = match path {
+            Some(path) => path,
+            None => return ConfigFile::default(),
+        };
+        let contents = fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("failed to read config file {:?}: {}", path, e));
+        toml::from_str(&contents)
+            .unwrap_or_else(|e| panic!("failed to parse config file {:?}: {}", path, e))
+    }
+}
+
 #[derive(Debug)]
 pub struct Config {
     pub log: stderrlog::StdErrLog,
@@ -28,75 +108,149 @@ pub struct Config {
     pub cookie: String,                // for bitcoind JSONRPC authentication ("USER:PASSWORD")
     pub electrum_rpc_addr: SocketAddr, // for serving Electrum clients
     pub monitoring_addr: SocketAddr,   // for Prometheus monitoring
+    pub db_compaction_profile: DbCompactionProfile, // RocksDB tuning profile
+    pub max_open_files: Option<u64>, // fd-limit / RocksDB max_open_files target
+    pub action: Action,             // import / compact / export / version
 }
 
 impl Config {
     pub fn from_args() -> Config {
         let m = App::new("Electrum Rust Server")
             .version(crate_version!())
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                SubCommand::with_name("import")
+                    .about("Bulk-import the chain into the index"),
+            )
+            .subcommand(
+                SubCommand::with_name("compact")
+                    .about("Open the index and run a standalone full RocksDB compaction"),
+            )
+            .subcommand(
+                SubCommand::with_name("version")
+                    .about("Report the on-disk DB schema version without opening it for write"),
+            )
+            .subcommand(
+                SubCommand::with_name("export")
+                    .about("Stream iter_scan over a key prefix to stdout or a file")
+                    .arg(
+                        Arg::with_name("prefix")
+                            .help("Hex-encoded key prefix to scan")
+                            .required(true),
+                    )
+                    .arg(
+                        Arg::with_name("output")
+                            .long("output")
+                            .help("File to write to (default: stdout)")
+                            .takes_value(true),
+                    ),
+            )
             .arg(
                 Arg::with_name("verbosity")
                     .short("v")
                     .multiple(true)
+                    .global(true)
                     .help("Increase logging verbosity"),
             )
             .arg(
                 Arg::with_name("timestamp")
                     .long("timestamp")
+                    .global(true)
                     .help("Prepend log lines with a timestamp"),
             )
+            .arg(
+                Arg::with_name("conf")
+                    .long("conf")
+                    .global(true)
+                    .help("Path to a TOML config file (default: search for electrs.toml in the daemon dir)")
+                    .takes_value(true),
+            )
             .arg(
                 Arg::with_name("db_dir")
                     .long("db-dir")
+                    .global(true)
                     .help("Directory to store index database (deafult: ./db/)")
                     .takes_value(true),
             )
             .arg(
                 Arg::with_name("daemon_dir")
                     .long("daemon-dir")
+                    .global(true)
                     .help("Data directory of Bitcoind (default: ~/.bitcoin/)")
                     .takes_value(true),
             )
             .arg(
                 Arg::with_name("cookie")
                     .long("cookie")
+                    .global(true)
                     .help("JSONRPC authentication cookie ('USER:PASSWORD', default: read from ~/.bitcoin/.cookie)")
                     .takes_value(true),
             )
             .arg(
                 Arg::with_name("network")
+                    .global(true)
                     .help("Select Bitcoin network type ('mainnet', 'testnet' or 'regtest')")
                     .takes_value(true),
             )
             .arg(
                 Arg::with_name("electrum_rpc_addr")
                     .long("electrum-rpc-addr")
+                    .global(true)
                     .help("Electrum server JSONRPC 'addr:port' to listen on (default: '127.0.0.1:50001' for mainnet, '127.0.0.1:60001' for testnet and '127.0.0.1:60401' for regtest)")
                     .takes_value(true),
             )
             .arg(
                 Arg::with_name("daemon_rpc_addr")
                     .long("daemon-rpc-addr")
+                    .global(true)
                     .help("Bitcoin daemon JSONRPC 'addr:port' to connect (default: 127.0.0.1:8332 for mainnet, 127.0.0.1:18332 for testnet and 127.0.0.1:18443 for regtest)")
                     .takes_value(true),
             )
             .arg(
                 Arg::with_name("monitoring_addr")
                     .long("monitoring-addr")
+                    .global(true)
                     .help("Prometheus monitoring 'addr:port' to listen on (default: 127.0.0.1:42024)")
                     .takes_value(true),
             )
+            .arg(
+                Arg::with_name("db_compaction_profile")
+                    .long("db-compaction-profile")
+                    .global(true)
+                    .help("RocksDB tuning profile to match the storage hardware ('default', 'hdd' or 'ssd')")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("max_open_files")
+                    .long("max-open-files")
+                    .global(true)
+                    .help("Target for the process fd limit and RocksDB's max_open_files (default: raise soft limit to the hard limit)")
+                    .takes_value(true),
+            )
             .get_matches();
 
-        let network_name = m.value_of("network").unwrap_or("mainnet");
-        let network_type = match network_name {
+        // Resolve the daemon dir first (CLI flag or default) so we know
+        // where to look for an implicit `electrs.toml`, then load the file
+        // so its values can fill in wherever a CLI flag was not given.
+        let default_daemon_dir = m.value_of("daemon_dir")
+            .map(|p| p.to_owned())
+            .unwrap_or_else(|| {
+                let mut default_dir = home_dir().expect("no homedir");
+                default_dir.push(".bitcoin");
+                default_dir.to_str().expect("non-UTF8 homedir").to_owned()
+            });
+        let conf_file = ConfigFile::load(m.value_of("conf"), &default_daemon_dir);
+
+        let network_name = resolve(m.value_of("network"), conf_file.network.clone(), "mainnet");
+        let network_type = match network_name.as_str() {
             "mainnet" => Network::Mainnet,
             "testnet" => Network::Testnet,
             "regtest" => Network::Regtest,
             _ => panic!("unsupported Bitcoin network: {:?}", network_name),
         };
-        let db_dir = Path::new(m.value_of("db_dir").unwrap_or("./db"));
-        let db_path = db_dir.join(network_name);
+        let db_dir = Path::new(&resolve(m.value_of("db_dir"), conf_file.db_dir.clone(), "./db"))
+            .to_path_buf();
+        let db_path = db_dir.join(&network_name);
 
         let default_daemon_port = match network_type {
             Network::Mainnet => 8332,
@@ -109,26 +263,30 @@ impl Config {
             Network::Regtest => 60401,
         };
 
-        let daemon_rpc_addr: SocketAddr = m.value_of("daemon_rpc_addr")
-            .unwrap_or(&format!("127.0.0.1:{}", default_daemon_port))
-            .parse()
+        let daemon_rpc_addr: SocketAddr = resolve(
+            m.value_of("daemon_rpc_addr"),
+            conf_file.daemon_rpc_addr.clone(),
+            &format!("127.0.0.1:{}", default_daemon_port),
+        ).parse()
             .expect("invalid Bitcoind RPC address");
-        let electrum_rpc_addr: SocketAddr = m.value_of("electrum_rpc_addr")
-            .unwrap_or(&format!("127.0.0.1:{}", default_electrum_port))
-            .parse()
+        let electrum_rpc_addr: SocketAddr = resolve(
+            m.value_of("electrum_rpc_addr"),
+            conf_file.electrum_rpc_addr.clone(),
+            &format!("127.0.0.1:{}", default_electrum_port),
+        ).parse()
             .expect("invalid Electrum RPC address");
-        let monitoring_addr: SocketAddr = m.value_of("monitoring_addr")
-            .unwrap_or("127.0.0.1:42024")
-            .parse()
+        let monitoring_addr: SocketAddr = resolve(
+            m.value_of("monitoring_addr"),
+            conf_file.monitoring_addr.clone(),
+            "127.0.0.1:42024",
+        ).parse()
             .expect("invalid Prometheus monitoring address");
 
-        let mut daemon_dir = m.value_of("daemon_dir")
-            .map(|p| PathBuf::from(p))
-            .unwrap_or_else(|| {
-                let mut default_dir = home_dir().expect("no homedir");
-                default_dir.push(".bitcoin");
-                default_dir
-            });
+        let mut daemon_dir = PathBuf::from(resolve(
+            m.value_of("daemon_dir"),
+            conf_file.daemon_dir.clone(),
+            &default_daemon_dir,
+        ));
         match network_type {
             Network::Mainnet => (),
             Network::Testnet => daemon_dir.push("testnet3"),
@@ -136,8 +294,31 @@ impl Config {
         }
         let cookie = m.value_of("cookie")
             .map(|s| s.to_owned())
+            .or_else(|| conf_file.cookie.clone())
             .unwrap_or_else(|| read_cookie(&daemon_dir).unwrap());
 
+        let db_compaction_profile = DbCompactionProfile::from_str(&resolve(
+            m.value_of("db_compaction_profile"),
+            conf_file.db_compaction_profile.clone(),
+            "default",
+        ));
+
+        let max_open_files = match m.value_of("max_open_files") {
+            Some(s) => Some(s.parse().expect("invalid max-open-files")),
+            None => conf_file.max_open_files,
+        }.map(validate_max_open_files);
+
+        let action = match m.subcommand() {
+            ("import", Some(_)) => Action::Import,
+            ("compact", Some(_)) => Action::Compact,
+            ("version", Some(_)) => Action::Version,
+            ("export", Some(export_m)) => Action::Export {
+                prefix: parse_hex_prefix(export_m.value_of("prefix").unwrap()),
+                output: export_m.value_of("output").map(PathBuf::from),
+            },
+            _ => unreachable!("clap enforces a subcommand via SubcommandRequiredElseHelp"),
+        };
+
         let mut log = stderrlog::new();
         log.verbosity(m.occurrences_of("verbosity") as usize);
         log.timestamp(if m.is_present("timestamp") {
@@ -155,8 +336,53 @@ impl Config {
             cookie,
             electrum_rpc_addr,
             monitoring_addr,
+            db_compaction_profile,
+            max_open_files,
+            action,
         };
         eprintln!("{:?}", config);
         config
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_prefers_cli_over_file_over_default() {
+        assert_eq!(resolve(Some("cli"), Some("file".to_owned()), "default"), "cli");
+        assert_eq!(resolve(None, Some("file".to_owned()), "default"), "file");
+        assert_eq!(resolve(None, None, "default"), "default");
+    }
+
+    #[test]
+    fn config_file_load_with_no_path_and_no_implicit_file_is_empty() {
+        let conf_file = ConfigFile::load(None, "/nonexistent/daemon/dir");
+        assert!(conf_file.network.is_none());
+        assert!(conf_file.db_dir.is_none());
+    }
+
+    #[test]
+    fn config_file_load_reads_explicit_path() {
+        let mut path = std::env::temp_dir();
+        path.push("electrs_config_test.toml");
+        fs::write(&path, "network = \"testnet\"\ndb_dir = \"/tmp/db\"\n").unwrap();
+        let conf_file = ConfigFile::load(Some(path.to_str().unwrap()), "/nonexistent/daemon/dir");
+        assert_eq!(conf_file.network.as_deref(), Some("testnet"));
+        assert_eq!(conf_file.db_dir.as_deref(), Some("/tmp/db"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn validate_max_open_files_accepts_values_within_i32_range() {
+        assert_eq!(validate_max_open_files(100_000), 100_000);
+        assert_eq!(validate_max_open_files(i32::max_value() as u64), i32::max_value() as u64);
+    }
+
+    #[test]
+    #[should_panic(expected = "exceeds the maximum RocksDB max_open_files value")]
+    fn validate_max_open_files_rejects_values_beyond_i32_range() {
+        validate_max_open_files(i32::max_value() as u64 + 1);
+    }
+}
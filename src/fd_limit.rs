@@ -0,0 +1,28 @@
+use libc;
+
+// raises the soft RLIMIT_NOFILE toward target (or the hard limit), logging before/after
+pub fn raise_fd_limit(target: Option<u64>) {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        warn!("failed to query RLIMIT_NOFILE, leaving fd limit unchanged");
+        return;
+    }
+    let before = limit.rlim_cur;
+    let ceiling = target.unwrap_or(limit.rlim_max).min(limit.rlim_max);
+    if ceiling <= before {
+        debug!("fd limit already at {} (hard limit {})", before, limit.rlim_max);
+        return;
+    }
+    limit.rlim_cur = ceiling;
+    if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) } != 0 {
+        warn!(
+            "failed to raise fd limit from {} to {}, leaving it unchanged",
+            before, ceiling
+        );
+        return;
+    }
+    info!("raised fd limit from {} to {}", before, ceiling);
+}
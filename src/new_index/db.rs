@@ -1,12 +1,15 @@
+use num_cpus;
 use rocksdb;
 
 use crate::config::Config;
+use crate::errors::*;
+use crate::new_index::migrations::migration_path;
 use crate::util::{bincode, Bytes};
 use derivative::Derivative;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-static DB_VERSION: u32 = 1;
+static CURRENT_VERSION: u32 = 1;
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct DBRow {
@@ -87,6 +90,25 @@ pub enum DBFlush {
     Enable,
 }
 
+// RocksDB tuning profile, selected via --db-compaction-profile
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DbCompactionProfile {
+    Default,
+    Hdd,
+    Ssd,
+}
+
+impl DbCompactionProfile {
+    pub fn from_str(s: &str) -> DbCompactionProfile {
+        match s {
+            "default" => DbCompactionProfile::Default,
+            "hdd" => DbCompactionProfile::Hdd,
+            "ssd" => DbCompactionProfile::Ssd,
+            _ => panic!("unsupported DB compaction profile: {:?}", s),
+        }
+    }
+}
+
 impl DB {
     pub fn open(path: &Path, config: &Config) -> DB {
         debug!("opening DB at {:?}", path);
@@ -238,36 +260,129 @@ impl DB {
     }
 
     fn verify_compatibility(&self, config: &Config) {
-        let mut compatibility_bytes = bincode::serialize_little(&DB_VERSION).unwrap();
-
-        if config.light_mode {
-            // append a byte to indicate light_mode is enabled.
-            // we're not letting bincode serialize this so that the compatiblity bytes won't change
-            // (and require a reindex) when light_mode is disabled. this should be chagned the next
-            // time we bump DB_VERSION and require a re-index anyway.
-            compatibility_bytes.push(1);
-        }
-
         match self.get(b"V") {
-            None => self.put(b"V", &compatibility_bytes),
-            Some(ref x) if x != &compatibility_bytes => {
-                panic!("Incompatible database found. Please reindex.")
+            None => self.put(b"V", &compatibility_bytes(CURRENT_VERSION, config.light_mode)),
+            Some(ref x) => {
+                let (stored_version, stored_light_mode) = parse_compatibility_bytes(x);
+                if stored_light_mode != config.light_mode {
+                    panic!("Incompatible database found. Please reindex.");
+                }
+                if stored_version == CURRENT_VERSION {
+                    return;
+                }
+                match migration_path(stored_version, CURRENT_VERSION) {
+                    Some(migrations) => {
+                        for step in migrations {
+                            info!(
+                                "running DB migration: {} -> {}",
+                                step.from_version, step.to_version
+                            );
+                            (step.run)(self).unwrap_or_else(|e| {
+                                panic!(
+                                    "migration {} -> {} failed: {}",
+                                    step.from_version, step.to_version, e
+                                )
+                            });
+                            self.put_sync(
+                                b"V",
+                                &compatibility_bytes(step.to_version, config.light_mode),
+                            );
+                        }
+                    }
+                    None => panic!("Incompatible database found. Please reindex."),
+                }
             }
-            Some(_) => (),
         }
     }
 }
 
+fn compatibility_bytes(version: u32, light_mode: bool) -> Vec<u8> {
+    let mut bytes = bincode::serialize_little(&version).unwrap();
+    if light_mode {
+        // we're not letting bincode serialize this so that the compatiblity bytes won't change
+        // (and require a reindex) when light_mode is disabled. this should be chagned the next
+        // time we bump CURRENT_VERSION and require a re-index anyway.
+        bytes.push(1);
+    }
+    bytes
+}
+
+fn parse_compatibility_bytes(bytes: &[u8]) -> (u32, bool) {
+    if bytes.len() < 4 {
+        panic!("corrupt V key: expected at least 4 bytes, found {}", bytes.len());
+    }
+    let version = bincode::deserialize_little(&bytes[..4]).expect("corrupt V key");
+    let light_mode = bytes.len() > 4 && bytes[4] == 1;
+    (version, light_mode)
+}
+
+// reads the on-disk version without opening the DB for write
+pub fn inspect_version(path: &Path, config: &Config) -> Result<Option<u32>> {
+    if !path.exists() {
+        // open_for_read_only can't create a DB, so it errors on a missing path
+        return Ok(None);
+    }
+    let db_opts = build_db_options(config);
+    let db = rocksdb::DB::open_for_read_only(&db_opts, path, false)
+        .chain_err(|| format!("failed to open {:?} for version inspection", path))?;
+    Ok(db.get(b"V")
+        .chain_err(|| "failed to read V key")?
+        .map(|bytes| parse_compatibility_bytes(&bytes).0))
+}
+
 fn build_db_options(config: &Config) -> rocksdb::Options {
     let mut db_opts = rocksdb::Options::default();
     db_opts.create_if_missing(!config.read_only);
-    db_opts.set_max_open_files(100_000);
+    // Config::from_args already rejects values that don't fit in an i32.
+    db_opts.set_max_open_files(config.max_open_files.unwrap_or(100_000) as i32);
     db_opts.set_compaction_style(rocksdb::DBCompactionStyle::Level);
     db_opts.set_compression_type(rocksdb::DBCompressionType::Snappy);
-    db_opts.set_target_file_size_base(1_073_741_824);
-    db_opts.set_write_buffer_size(256 << 20);
     db_opts.set_disable_auto_compactions(true);
-    db_opts.set_compaction_readahead_size(1 << 20);
-    db_opts.increase_parallelism(2);
+
+    match config.db_compaction_profile {
+        DbCompactionProfile::Default => {
+            db_opts.set_target_file_size_base(1_073_741_824);
+            db_opts.set_write_buffer_size(256 << 20);
+            db_opts.set_compaction_readahead_size(1 << 20);
+            db_opts.increase_parallelism(2);
+        }
+        DbCompactionProfile::Hdd => {
+            // Fewer, larger files and a long readahead minimize seeks on
+            // spinning disks at the cost of write amplification.
+            db_opts.set_target_file_size_base(2_147_483_648);
+            db_opts.set_write_buffer_size(512 << 20);
+            db_opts.set_compaction_readahead_size(16 << 20);
+            db_opts.increase_parallelism(2);
+            db_opts.set_max_background_jobs(2);
+        }
+        DbCompactionProfile::Ssd => {
+            // NVMe/SSDs don't benefit from large sequential readahead, so
+            // prefer smaller files and more compaction parallelism instead.
+            db_opts.set_target_file_size_base(268_435_456);
+            db_opts.set_write_buffer_size(128 << 20);
+            db_opts.set_compaction_readahead_size(256 << 10);
+            let cores = num_cpus::get() as i32;
+            db_opts.increase_parallelism(cores);
+            db_opts.set_max_background_jobs(cores);
+        }
+    }
     db_opts
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn db_compaction_profile_from_str_parses_known_names() {
+        assert_eq!(DbCompactionProfile::from_str("default"), DbCompactionProfile::Default);
+        assert_eq!(DbCompactionProfile::from_str("hdd"), DbCompactionProfile::Hdd);
+        assert_eq!(DbCompactionProfile::from_str("ssd"), DbCompactionProfile::Ssd);
+    }
+
+    #[test]
+    #[should_panic(expected = "unsupported DB compaction profile")]
+    fn db_compaction_profile_from_str_rejects_unknown_names() {
+        DbCompactionProfile::from_str("nvme");
+    }
+}
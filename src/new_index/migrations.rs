@@ -0,0 +1,78 @@
+use std::collections::HashSet;
+
+use crate::errors::*;
+use crate::new_index::db::DB;
+
+pub struct Migration {
+    pub from_version: u32,
+    pub to_version: u32,
+    pub run: fn(&DB) -> Result<()>,
+}
+
+// when bumping CURRENT_VERSION, add a migration from the previous version here so
+// existing databases are upgraded in place instead of requiring a reindex
+pub static MIGRATIONS: &[Migration] = &[];
+
+// chain of migrations from `from` to `to`, or None if no contiguous path exists
+pub fn migration_path(from: u32, to: u32) -> Option<Vec<&'static Migration>> {
+    migration_path_over(MIGRATIONS, from, to)
+}
+
+// split out from migration_path so the chain-walk can be tested against a local table
+fn migration_path_over(migrations: &[Migration], from: u32, to: u32) -> Option<Vec<&Migration>> {
+    let mut path = vec![];
+    let mut version = from;
+    let mut seen = HashSet::new();
+    while version != to {
+        // guards against a cycle or duplicate from_version entries looping forever
+        if !seen.insert(version) {
+            return None;
+        }
+        let step = migrations.iter().find(|m| m.from_version == version)?;
+        path.push(step);
+        version = step.to_version;
+    }
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noop(_db: &DB) -> Result<()> {
+        Ok(())
+    }
+
+    #[test]
+    fn migration_path_already_current_is_empty() {
+        let table = [Migration { from_version: 1, to_version: 2, run: noop }];
+        assert_eq!(migration_path_over(&table, 3, 3).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn migration_path_walks_a_chain_in_order() {
+        let table = [
+            Migration { from_version: 1, to_version: 2, run: noop },
+            Migration { from_version: 2, to_version: 3, run: noop },
+        ];
+        let path = migration_path_over(&table, 1, 3).unwrap();
+        assert_eq!(path.len(), 2);
+        assert_eq!(path[0].from_version, 1);
+        assert_eq!(path[1].from_version, 2);
+    }
+
+    #[test]
+    fn migration_path_with_no_route_is_none() {
+        let table = [Migration { from_version: 1, to_version: 2, run: noop }];
+        assert!(migration_path_over(&table, 1, 5).is_none());
+    }
+
+    #[test]
+    fn migration_path_with_a_cycle_terminates_with_none() {
+        let table = [
+            Migration { from_version: 1, to_version: 2, run: noop },
+            Migration { from_version: 2, to_version: 1, run: noop },
+        ];
+        assert!(migration_path_over(&table, 1, 99).is_none());
+    }
+}